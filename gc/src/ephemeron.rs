@@ -0,0 +1,130 @@
+//! Ephemeron-based weak key/value pairs, the building block `WeakMap` and
+//! `WeakSet` are implemented on top of.
+
+use std::cell::RefCell;
+use std::ptr::NonNull;
+
+use crate::trace::{Finalize, Trace};
+use crate::weak::Weak;
+use crate::{Gc, Tracer};
+
+thread_local! {
+    /// Every live `Ephemeron` registers itself here when traced, so the
+    /// collector's fixpoint pass can find it after the normal mark phase.
+    pub(crate) static EPHEMERONS: RefCell<Vec<NonNull<dyn ErasedEphemeron>>> = RefCell::new(Vec::new());
+}
+
+/// The collector-facing half of `Ephemeron`, type-erased over `K`/`V` so the
+/// registry can hold ephemerons of unrelated key/value types together.
+pub(crate) trait ErasedEphemeron {
+    /// Whether this ephemeron's key is currently marked reachable.
+    fn key_marked(&self) -> bool;
+
+    /// Traces the value, since the key turned out to be reachable.
+    fn sweep(&self, tracer: &mut Tracer) -> bool;
+}
+
+/// A key/value pair whose value is only considered reachable while its key
+/// is independently reachable.
+///
+/// During collection the collector runs a fixpoint pass over every
+/// registered `Ephemeron`: as long as some pass traces a previously-unmarked
+/// key, the matching value is traced too (which may in turn mark further
+/// keys). Once a full pass marks nothing new, any `Ephemeron` whose key
+/// never got marked is left with a collectable value. This is what lets
+/// `WeakMap`/`WeakSet` hold values keyed by object identity without keeping
+/// the keys — or, transitively, the values — alive on their own.
+pub struct Ephemeron<K: Trace + 'static, V: Trace + 'static> {
+    key: Weak<K>,
+    value: RefCell<Option<V>>,
+}
+
+impl<K: Trace, V: Trace> Ephemeron<K, V> {
+    /// Creates an ephemeron pairing `key` with `value`.
+    pub fn new(key: &Gc<K>, value: V) -> Self {
+        Self {
+            key: Weak::new(key),
+            value: RefCell::new(Some(value)),
+        }
+    }
+
+    /// Returns the key, if it's still reachable.
+    pub fn key(&self) -> Option<Gc<K>> {
+        self.key.upgrade()
+    }
+}
+
+impl<K: Trace, V: Trace + Clone> Ephemeron<K, V> {
+    /// Returns a clone of the value, if the key is still reachable.
+    pub fn value(&self) -> Option<V> {
+        if self.key.upgrade().is_none() {
+            return None;
+        }
+        self.value.borrow().clone()
+    }
+}
+
+impl<K: Trace, V: Trace> ErasedEphemeron for Ephemeron<K, V> {
+    fn key_marked(&self) -> bool {
+        // Gate on whether the key was marked *this* mark phase, not on
+        // whether it has been swept: the fixpoint pass runs between the mark
+        // and sweep phases, so `Weak::upgrade`'s "has it been swept" check
+        // would read every key as reachable and defeat the weak-key
+        // semantics entirely.
+        self.key.is_marked()
+    }
+
+    fn sweep(&self, tracer: &mut Tracer) -> bool {
+        match self.value.borrow().as_ref() {
+            Some(value) => {
+                unsafe { value.trace(tracer) };
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<K: Trace, V: Trace> Drop for Ephemeron<K, V> {
+    fn drop(&mut self) {
+        // Without this, a dropped `Ephemeron` leaves a dangling pointer
+        // behind in `EPHEMERONS` for the next fixpoint pass to dereference.
+        let self_ptr = self as *mut Self as *const ();
+        EPHEMERONS.with(|ephemerons| {
+            ephemerons
+                .borrow_mut()
+                .retain(|ephemeron| ephemeron.as_ptr() as *const () != self_ptr);
+        });
+    }
+}
+
+impl<K: Trace, V: Trace> Finalize for Ephemeron<K, V> {}
+unsafe impl<K: Trace, V: Trace> Trace for Ephemeron<K, V> {
+    unsafe fn trace(&self, _tracer: &mut Tracer) {
+        // Registering for the fixpoint pass, rather than tracing the key or
+        // value here directly, is what makes the key weak: whether the
+        // value ever gets traced depends entirely on the key turning out to
+        // be reachable through some other path.
+        let erased: NonNull<dyn ErasedEphemeron> = NonNull::from(self);
+        EPHEMERONS.with(|ephemerons| {
+            let mut ephemerons = ephemerons.borrow_mut();
+            // One mark phase can reach the same ephemeron more than once
+            // (e.g. through several `Rc`s sharing it), so without this
+            // check it would register (and thus duplicate) itself.
+            let already_registered = ephemerons
+                .iter()
+                .any(|existing| existing.as_ptr() as *const () == erased.as_ptr() as *const ());
+            if !already_registered {
+                ephemerons.push(erased);
+            }
+        });
+    }
+
+    unsafe fn root(&self) {}
+
+    unsafe fn unroot(&self) {}
+
+    fn finalize_glue(&self) {
+        Finalize::finalize(self);
+    }
+}
@@ -0,0 +1,100 @@
+//! Collector-driven finalizer callbacks, the primitive JavaScript's
+//! `FinalizationRegistry` is built on.
+
+use std::collections::VecDeque;
+
+use crate::internals::Gc;
+use crate::trace::{Finalize, Trace};
+use crate::weak::Weak;
+
+struct Entry<T: Trace + 'static, H: Trace + 'static> {
+    target: Weak<T>,
+    held_value: H,
+    token: Option<*const ()>,
+}
+
+/// A registry pairing GC-managed targets with a held value to hand back
+/// once the target becomes unreachable.
+///
+/// Registration holds the target only weakly, so registering never keeps it
+/// alive; the held value is held strongly until it's drained. This mirrors
+/// JavaScript's `FinalizationRegistry`: `register` doesn't observe
+/// reclamation directly, it just arranges for `held_value` to show up later
+/// in [`drain_finalizers`](Self::drain_finalizers), which the interpreter
+/// pumps between statements to invoke the registered JS callback.
+pub struct FinalizationRegistry<T: Trace + 'static, H: Trace + 'static> {
+    entries: Vec<Entry<T, H>>,
+    // The token travels with the held value: a target can be swept (and its
+    // token dropped from `entries`) before the interpreter ever drains the
+    // queue, and `unregister` must still be able to pull it back out.
+    pending: VecDeque<(Option<*const ()>, H)>,
+}
+
+impl<T: Trace, H: Trace> Default for FinalizationRegistry<T, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Trace, H: Trace> FinalizationRegistry<T, H> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Registers `target`: once it becomes unreachable, `held_value` is
+    /// queued for [`drain_finalizers`](Self::drain_finalizers). `token`, if
+    /// given, identifies the registration for a later
+    /// [`unregister`](Self::unregister) call.
+    pub fn register(&mut self, target: &Gc<T>, held_value: H, token: Option<&Gc<T>>) {
+        self.entries.push(Entry {
+            target: Weak::new(target),
+            held_value,
+            token: token.map(Gc::as_ptr),
+        });
+    }
+
+    /// Removes every registration — pending *or* already swept but not yet
+    /// drained — made with this `token`. Entries are matched by the object
+    /// identity of the `Gc` passed as `token` at registration time.
+    pub fn unregister(&mut self, token: &Gc<T>) {
+        let token = Some(Gc::as_ptr(token));
+        self.entries.retain(|entry| entry.token != token);
+        self.pending.retain(|(entry_token, _)| *entry_token != token);
+    }
+
+    /// Called by the collector after a sweep: moves the held value of every
+    /// entry whose target didn't survive onto the pending-finalizers queue.
+    pub(crate) fn sweep(&mut self) {
+        let mut i = 0;
+        while i < self.entries.len() {
+            if self.entries[i].target.upgrade().is_some() {
+                i += 1;
+            } else {
+                let entry = self.entries.remove(i);
+                self.pending.push_back((entry.token, entry.held_value));
+            }
+        }
+    }
+
+    /// Drains every pending held value, in the order their targets were
+    /// swept.
+    pub fn drain_finalizers(&mut self) -> impl Iterator<Item = H> + '_ {
+        self.pending.drain(..).map(|(_, held_value)| held_value)
+    }
+}
+
+impl<T: Trace, H: Trace> Finalize for FinalizationRegistry<T, H> {}
+unsafe impl<T: Trace, H: Trace> Trace for FinalizationRegistry<T, H> {
+    crate::custom_trace!(this, tracer, {
+        for entry in &this.entries {
+            mark(&entry.held_value, tracer);
+        }
+        for (_, held_value) in &this.pending {
+            mark(held_value, tracer);
+        }
+    });
+}
@@ -0,0 +1,206 @@
+//! The heap representation the collector manages: `GcBox`, the `Gc` handle
+//! applications hold, and the `Tracer` that drives the mark phase.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+use crate::trace::{Finalize, Trace};
+
+/// Bookkeeping stored alongside every value the collector manages.
+struct GcBoxHeader {
+    marked: Cell<bool>,
+    root_count: Cell<usize>,
+    /// Set once the collector has swept this box's value. Outstanding
+    /// `Weak`s check this to know their pointee is gone.
+    dropped: Cell<bool>,
+}
+
+impl GcBoxHeader {
+    fn new() -> Self {
+        Self {
+            marked: Cell::new(false),
+            root_count: Cell::new(1),
+            dropped: Cell::new(false),
+        }
+    }
+}
+
+/// A heap allocation owned by the collector: bookkeeping plus the traced value.
+pub struct GcBox<T: Trace + ?Sized + 'static> {
+    header: GcBoxHeader,
+    data: T,
+}
+
+impl<T: Trace + ?Sized> GcBox<T> {
+    /// Whether the box is still marked reachable from the last mark phase.
+    pub(crate) fn is_marked(&self) -> bool {
+        self.header.marked.get()
+    }
+
+    /// Whether the collector has already swept this box's value.
+    pub(crate) fn is_dropped(&self) -> bool {
+        self.header.dropped.get()
+    }
+}
+
+/// A type-erased pointer to a `GcBox`, used so the mark queue can hold
+/// `Gc`s of unrelated concrete types side by side.
+type ErasedGcBox = NonNull<GcBox<dyn Trace>>;
+
+/// A handle to a garbage-collected value.
+///
+/// Cloning a `Gc` is cheap: it shares the underlying allocation rather than
+/// copying `T`.
+pub struct Gc<T: Trace + 'static> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T: Trace> Gc<T> {
+    fn inner(&self) -> &GcBox<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns the raw pointer backing this handle, for use by `Weak`.
+    pub(crate) fn ptr(&self) -> NonNull<GcBox<T>> {
+        self.ptr
+    }
+
+    /// Wraps an existing allocation without going through the allocator,
+    /// bumping its root count. Used by `Weak::upgrade` and `Gc::clone`.
+    ///
+    /// # Safety
+    /// `ptr` must point at a live `GcBox<T>`.
+    pub(crate) unsafe fn from_raw(ptr: NonNull<GcBox<T>>) -> Self {
+        let header = &ptr.as_ref().header;
+        header.root_count.set(header.root_count.get() + 1);
+        Self { ptr }
+    }
+
+    /// Returns a stable address identifying this allocation, for identity
+    /// comparisons (e.g. `FinalizationRegistry` unregister tokens).
+    pub fn as_ptr(this: &Gc<T>) -> *const () {
+        this.ptr.as_ptr() as *const ()
+    }
+}
+
+impl<T: Trace> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        unsafe { Gc::from_raw(self.ptr) }
+    }
+}
+
+impl<T: Trace> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().data
+    }
+}
+
+impl<T: Trace> Finalize for Gc<T> {}
+unsafe impl<T: Trace> Trace for Gc<T> {
+    #[inline]
+    unsafe fn trace(&self, tracer: &mut Tracer) {
+        tracer.enqueue(self.ptr);
+    }
+
+    #[inline]
+    unsafe fn root(&self) {
+        self.inner().header.root_count.set(self.inner().header.root_count.get() + 1);
+    }
+
+    #[inline]
+    unsafe fn unroot(&self) {
+        self.inner().header.root_count.set(self.inner().header.root_count.get() - 1);
+    }
+
+    #[inline]
+    fn finalize_glue(&self) {
+        Finalize::finalize(self);
+    }
+}
+
+/// Drives the collector's mark phase without recursing through the object
+/// graph.
+///
+/// Every leaf `Gc<T>` enqueues its pointee here instead of tracing into it
+/// directly; the collector then drains the queue, tracing one node at a
+/// time and letting that node enqueue its own children in turn. This bounds
+/// the traversal by heap size rather than Rust call stack depth, so a
+/// deeply nested value (a long linked list, a deeply chained
+/// object/array, a recursive closure environment) can't blow the stack
+/// during collection.
+pub struct Tracer {
+    queue: VecDeque<ErasedGcBox>,
+}
+
+impl Tracer {
+    /// Creates an empty tracer, starting a new collection cycle.
+    ///
+    /// This also clears the ephemeron registry: every `Ephemeron` still
+    /// reachable re-registers itself when `trace` visits it this cycle, so
+    /// starting from empty keeps the registry from accumulating entries for
+    /// ephemerons an earlier cycle's `Drop` missed through some other bug.
+    pub(crate) fn new() -> Self {
+        crate::ephemeron::EPHEMERONS.with(|ephemerons| ephemerons.borrow_mut().clear());
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Enqueues a `Gc` to be traced, unless it has already been visited.
+    ///
+    /// The box is marked here, at enqueue time, rather than when it's
+    /// popped: that way a cycle that reaches the same node twice before
+    /// it's ever dequeued only enqueues it once, and the traversal always
+    /// terminates.
+    pub fn enqueue<T: Trace>(&mut self, gc: NonNull<GcBox<T>>) {
+        let header = unsafe { &gc.as_ref().header };
+        if header.marked.replace(true) {
+            return;
+        }
+        let erased: ErasedGcBox = unsafe { NonNull::new_unchecked(gc.as_ptr() as *mut GcBox<dyn Trace>) };
+        self.queue.push_back(erased);
+    }
+
+    /// Drains the queue, tracing each node and enqueuing the children it
+    /// marks, until nothing is left to visit.
+    pub(crate) fn run(&mut self) {
+        while let Some(node) = self.queue.pop_front() {
+            unsafe { node.as_ref().data.trace(self) };
+        }
+    }
+
+    /// Runs the ephemeron fixpoint pass: repeatedly sweeps every registered
+    /// `Ephemeron`, tracing the value of any whose key is marked, until a
+    /// full pass traces nothing new. Ephemerons whose key is still unmarked
+    /// after that are left with a collectable value.
+    ///
+    /// Must run after `run()` has finished draining the root-reachable
+    /// graph, since it relies on `GcBox::is_marked` reflecting that pass.
+    pub(crate) fn run_ephemeron_fixpoint(&mut self) {
+        loop {
+            let mut traced_any = false;
+            crate::ephemeron::EPHEMERONS.with(|ephemerons| {
+                ephemerons.borrow_mut().retain(|ephemeron| {
+                    // SAFETY: entries are removed before the `Ephemeron` they
+                    // point at is dropped (see `Ephemeron::drop`).
+                    let ephemeron = unsafe { ephemeron.as_ref() };
+                    if !ephemeron.key_marked() {
+                        return true;
+                    }
+                    traced_any |= ephemeron.sweep(self);
+                    // A swept ephemeron's value is now reachable through the
+                    // normal mark queue; no need to keep re-visiting it.
+                    false
+                });
+            });
+            self.run();
+            if !traced_any {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! A tracing garbage collector for values reachable from the interpreter.
+//!
+//! [`trace`] defines the `Trace`/`Finalize` traits that every GC-managed
+//! value implements, and [`Tracer`] is the queue the collector drives to
+//! walk the object graph during the mark phase.
+
+pub mod trace;
+
+mod ephemeron;
+mod finalization;
+mod internals;
+mod weak;
+
+pub use ephemeron::Ephemeron;
+pub use finalization::FinalizationRegistry;
+pub use internals::{Gc, Tracer};
+pub use trace::{Finalize, Trace};
+pub use weak::Weak;
@@ -1,8 +1,18 @@
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
 use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap, HashSet, LinkedList, VecDeque};
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize};
 
+use crate::Tracer;
+
 /// The Finalize trait. Can be specialized for a specific type to define
 /// finalization logic for that type.
 pub trait Finalize {
@@ -19,8 +29,10 @@ impl<T: ?Sized> Finalize for T {
 
 /// The Trace trait, which needs to be implemented on garbage-collected objects.
 pub unsafe trait Trace: Finalize {
-    /// Marks all contained `Gc`s.
-    unsafe fn trace(&self);
+    /// Marks all contained `Gc`s by enqueuing them onto `tracer` rather than
+    /// recursing into them directly, so the mark phase is bounded by heap
+    /// size instead of the depth of the object graph.
+    unsafe fn trace(&self, tracer: &mut Tracer);
 
     /// Increments the root-count of all contained `Gc`s.
     unsafe fn root(&self);
@@ -40,7 +52,7 @@ pub unsafe trait Trace: Finalize {
 macro_rules! unsafe_empty_trace {
     () => {
         #[inline]
-        unsafe fn trace(&self) {}
+        unsafe fn trace(&self, _tracer: &mut $crate::Tracer) {}
         #[inline]
         unsafe fn root(&self) {}
         #[inline]
@@ -54,17 +66,24 @@ macro_rules! unsafe_empty_trace {
 
 /// This rule implements the trace method.
 ///
-/// You define a `this` parameter name and pass in a body, which should call `mark` on every
-/// traceable element inside the body. The mark implementation will automatically delegate to the
-/// correct method on the argument.
+/// You define a `this` parameter name and a `tracer` parameter name, and pass
+/// in a body which should call `mark(value, tracer)` on every traceable
+/// element inside the body. The mark implementation will automatically
+/// delegate to the correct method on the argument.
+///
+/// `mark` stays a plain `fn` item rather than a closure: items defined by a
+/// macro aren't hygienic, so (unlike a `let`-bound closure) its name remains
+/// visible to the `$body` the caller supplies. Since items also can't
+/// capture an enclosing local, the tracer is threaded through explicitly as
+/// `mark`'s second argument instead, bound the same way `$this` is.
 #[macro_export]
 macro_rules! custom_trace {
-    ($this:ident, $body:expr) => {
+    ($this:ident, $tracer:ident, $body:expr) => {
         #[inline]
-        unsafe fn trace(&self) {
+        unsafe fn trace(&self, $tracer: &mut $crate::Tracer) {
             #[inline]
-            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T) {
-                $crate::Trace::trace(it);
+            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T, tracer: &mut $crate::Tracer) {
+                $crate::Trace::trace(it, tracer);
             }
             let $this = self;
             $body
@@ -72,18 +91,20 @@ macro_rules! custom_trace {
         #[inline]
         unsafe fn root(&self) {
             #[inline]
-            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T) {
+            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T, _tracer: ()) {
                 $crate::Trace::root(it);
             }
+            let $tracer = ();
             let $this = self;
             $body
         }
         #[inline]
         unsafe fn unroot(&self) {
             #[inline]
-            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T) {
+            unsafe fn mark<T: $crate::Trace + ?Sized>(it: &T, _tracer: ()) {
                 $crate::Trace::unroot(it);
             }
+            let $tracer = ();
             let $this = self;
             $body
         }
@@ -91,9 +112,10 @@ macro_rules! custom_trace {
         fn finalize_glue(&self) {
             $crate::Finalize::finalize(self);
             #[inline]
-            fn mark<T: $crate::Trace + ?Sized>(it: &T) {
+            fn mark<T: $crate::Trace + ?Sized>(it: &T, _tracer: ()) {
                 $crate::Trace::finalize_glue(it);
             }
+            let $tracer = ();
             let $this = self;
             $body
         }
@@ -105,6 +127,11 @@ unsafe impl<T: ?Sized> Trace for &'static T {
     unsafe_empty_trace!();
 }
 
+impl<T: ?Sized> Finalize for PhantomData<T> {}
+unsafe impl<T: ?Sized> Trace for PhantomData<T> {
+    unsafe_empty_trace!();
+}
+
 macro_rules! simple_empty_finalize_trace {
     ($($T:ty),*) => {
         $(
@@ -116,22 +143,22 @@ macro_rules! simple_empty_finalize_trace {
 
 simple_empty_finalize_trace![(), isize, usize, bool, i8, u8, i16, u16, i32,
     u32, i64, u64, f32, f64, char, String, Path, PathBuf, AtomicBool,
-    AtomicIsize, AtomicUsize];
+    AtomicIsize, AtomicUsize, NonZeroI8, NonZeroU8, NonZeroI16, NonZeroU16,
+    NonZeroI32, NonZeroU32, NonZeroI64, NonZeroU64, NonZeroI128, NonZeroU128,
+    NonZeroIsize, NonZeroUsize];
 
 #[cfg(feature = "nightly")]
 simple_empty_finalize_trace![i128, u128];
 
-macro_rules! array_finalize_trace {
-    ($n:expr) => {
-        impl<T: Trace> Finalize for [T; $n] {}
-        unsafe impl<T: Trace> Trace for [T; $n] {
-            custom_trace!(this, {
-                for v in this {
-                    mark(v);
-                }
-            });
+// A single min-const-generics impl replaces the old `[T; 0]` through
+// `[T; 31]` hand-expansion, so arrays of any length can be traced.
+impl<T: Trace, const N: usize> Finalize for [T; N] {}
+unsafe impl<T: Trace, const N: usize> Trace for [T; N] {
+    custom_trace!(this, tracer, {
+        for v in this {
+            mark(v, tracer);
         }
-    }
+    });
 }
 
 macro_rules! fn_finalize_trace_one {
@@ -162,24 +189,21 @@ macro_rules! tuple_finalize_trace {
     ($($args:ident),*) => {
         impl<$($args),*> Finalize for ($($args,)*) {}
         unsafe impl<$($args: $crate::Trace),*> Trace for ($($args,)*) {
-            custom_trace!(this, {
+            custom_trace!(this, tracer, {
+                // A closure, rather than a nested `fn avoid_lints`: it needs
+                // to reborrow the enclosing `tracer` for each `mark` call,
+                // which a nested `fn` item can't capture. Reborrowing `tracer`
+                // on every call makes this closure `FnMut`, hence `let mut`.
                 #[allow(non_snake_case, unused_unsafe)]
-                fn avoid_lints<$($args: $crate::Trace),*>(&($(ref $args,)*): &($($args,)*)) {
-                    unsafe { $(mark($args);)* }
-                }
+                let mut avoid_lints = |&($(ref $args,)*): &($($args,)*)| {
+                    unsafe { $(mark($args, tracer);)* }
+                };
                 avoid_lints(this)
             });
         }
     }
 }
 
-macro_rules! array_finalize_trace_impls {
-    ($($n:expr),*) => {
-        $(
-            array_finalize_trace!($n);
-        )*
-    }
-}
 macro_rules! type_arg_tuple_based_finalized_trace_impls {
     ($(($($args:ident),*);)*) => {
         $(
@@ -189,12 +213,6 @@ macro_rules! type_arg_tuple_based_finalized_trace_impls {
     }
 }
 
-array_finalize_trace_impls![
-     0,  1,  2,  3,  4,  5,  6,  7,  8,  9,
-    10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
-    20, 21, 22, 23, 24, 25, 26, 27, 28, 29,
-    30, 31
-];
 type_arg_tuple_based_finalized_trace_impls![
     ();
     (A);
@@ -213,100 +231,146 @@ type_arg_tuple_based_finalized_trace_impls![
 
 impl<T: Trace + ?Sized> Finalize for Box<T> {}
 unsafe impl<T: Trace + ?Sized> Trace for Box<T> {
-    custom_trace!(this, {
-        mark(&**this);
+    custom_trace!(this, tracer, {
+        mark(&**this, tracer);
+    });
+}
+
+impl<T: Trace + ?Sized> Finalize for Rc<T> {}
+unsafe impl<T: Trace + ?Sized> Trace for Rc<T> {
+    custom_trace!(this, tracer, {
+        mark(&**this, tracer);
+    });
+}
+
+// `Cell::get` requires `T: Copy`, so that's all this can trace through.
+impl<T: Trace + Copy> Finalize for Cell<T> {}
+unsafe impl<T: Trace + Copy> Trace for Cell<T> {
+    custom_trace!(this, tracer, {
+        mark(&this.get(), tracer);
+    });
+}
+
+impl<T: Trace + ?Sized> Finalize for RefCell<T> {}
+unsafe impl<T: Trace + ?Sized> Trace for RefCell<T> {
+    custom_trace!(this, tracer, {
+        mark(&*this.borrow(), tracer);
+    });
+}
+
+impl<T: Trace> Finalize for OnceCell<T> {}
+unsafe impl<T: Trace> Trace for OnceCell<T> {
+    custom_trace!(this, tracer, {
+        if let Some(v) = this.get() {
+            mark(v, tracer);
+        }
+    });
+}
+
+impl<T: ToOwned + ?Sized + 'static> Finalize for Cow<'static, T> where T::Owned: Trace {}
+unsafe impl<T: ToOwned + ?Sized + 'static> Trace for Cow<'static, T>
+where
+    T::Owned: Trace,
+{
+    custom_trace!(this, tracer, {
+        if let Cow::Owned(ref v) = *this {
+            mark(v, tracer);
+        }
     });
 }
 
 impl<T: Trace> Finalize for Vec<T> {}
 unsafe impl<T: Trace> Trace for Vec<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for e in this {
-            mark(e);
+            mark(e, tracer);
         }
     });
 }
 
 impl<T: Trace> Finalize for Option<T> {}
 unsafe impl<T: Trace> Trace for Option<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         if let Some(ref v) = *this {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
 
 impl<T: Trace, E: Trace> Finalize for Result<T, E> {}
 unsafe impl<T: Trace, E: Trace> Trace for Result<T, E> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         match *this {
-            Ok(ref v) => mark(v),
-            Err(ref v) => mark(v),
+            Ok(ref v) => mark(v, tracer),
+            Err(ref v) => mark(v, tracer),
         }
     });
 }
 
 impl<T: Ord + Trace> Finalize for BinaryHeap<T> {}
 unsafe impl<T: Ord + Trace> Trace for BinaryHeap<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for v in this.into_iter() {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
 
 impl<K: Trace, V: Trace> Finalize for BTreeMap<K, V> {}
 unsafe impl<K: Trace, V: Trace> Trace for BTreeMap<K, V> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for (k, v) in this {
-            mark(k);
-            mark(v);
+            mark(k, tracer);
+            mark(v, tracer);
         }
     });
 }
 
 impl<T: Trace> Finalize for BTreeSet<T> {}
 unsafe impl<T: Trace> Trace for BTreeSet<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for v in this {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
 
-impl<K: Eq + Hash + Trace, V: Trace> Finalize for HashMap<K, V> {}
-unsafe impl<K: Eq + Hash + Trace, V: Trace> Trace for HashMap<K, V> {
-    custom_trace!(this, {
+// Parameterized over the hasher `S` (rather than pinned to the default
+// `RandomState`) so the engine can use a faster non-default hasher for its
+// variable environments without losing GC integration.
+impl<K: Eq + Hash + Trace, V: Trace, S: 'static> Finalize for HashMap<K, V, S> {}
+unsafe impl<K: Eq + Hash + Trace, V: Trace, S: 'static> Trace for HashMap<K, V, S> {
+    custom_trace!(this, tracer, {
         for (k, v) in this.iter() {
-            mark(k);
-            mark(v);
+            mark(k, tracer);
+            mark(v, tracer);
         }
     });
 }
 
-impl<T: Eq + Hash + Trace> Finalize for HashSet<T> {}
-unsafe impl<T: Eq + Hash + Trace> Trace for HashSet<T> {
-    custom_trace!(this, {
+impl<T: Eq + Hash + Trace, S: 'static> Finalize for HashSet<T, S> {}
+unsafe impl<T: Eq + Hash + Trace, S: 'static> Trace for HashSet<T, S> {
+    custom_trace!(this, tracer, {
         for v in this.iter() {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
 
 impl<T: Eq + Hash + Trace> Finalize for LinkedList<T> {}
 unsafe impl<T: Eq + Hash + Trace> Trace for LinkedList<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for v in this.iter() {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
 
 impl<T: Trace> Finalize for VecDeque<T> {}
 unsafe impl<T: Trace> Trace for VecDeque<T> {
-    custom_trace!(this, {
+    custom_trace!(this, tracer, {
         for v in this.iter() {
-            mark(v);
+            mark(v, tracer);
         }
     });
 }
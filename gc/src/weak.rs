@@ -0,0 +1,57 @@
+//! Weak references to GC-managed values.
+
+use std::ptr::NonNull;
+
+use crate::internals::{Gc, GcBox};
+use crate::trace::{Finalize, Trace};
+
+/// A handle to a garbage-collected value that does not keep it alive.
+///
+/// Upgrading a `Weak<T>` succeeds as long as some `Gc<T>` (or a chain of
+/// reachable `Gc`s) still roots the value; once the collector sweeps the
+/// pointee, `upgrade` returns `None` for the rest of the `Weak`'s lifetime.
+pub struct Weak<T: Trace + 'static> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<T: Trace> Weak<T> {
+    /// Creates a `Weak` pointing at the same allocation as `gc`, without
+    /// affecting its root count.
+    pub fn new(gc: &Gc<T>) -> Self {
+        Self { ptr: gc.ptr() }
+    }
+
+    /// Returns a new `Gc` to the pointee, or `None` if it has already been
+    /// collected.
+    pub fn upgrade(&self) -> Option<Gc<T>> {
+        if unsafe { self.ptr.as_ref() }.is_dropped() {
+            None
+        } else {
+            Some(unsafe { Gc::from_raw(self.ptr) })
+        }
+    }
+
+    /// Whether the pointee is marked reachable as of the mark phase
+    /// currently in progress.
+    ///
+    /// Unlike `upgrade`, which checks whether the collector has swept the
+    /// pointee, this reflects the *in-progress* mark: it's what the
+    /// ephemeron fixpoint pass gates on, since sweeping hasn't happened yet
+    /// when that pass runs.
+    pub(crate) fn is_marked(&self) -> bool {
+        unsafe { self.ptr.as_ref() }.is_marked()
+    }
+}
+
+impl<T: Trace> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T: Trace> Finalize for Weak<T> {}
+unsafe impl<T: Trace> Trace for Weak<T> {
+    // A `Weak` must never keep its pointee alive, so tracing it is a no-op:
+    // it deliberately does not enqueue the `GcBox` it points at.
+    crate::unsafe_empty_trace!();
+}